@@ -0,0 +1,132 @@
+//! Message-level framing over a byte-level [`Socket`](crate::Socket), so RPC
+//! style code can work with typed values instead of re-implementing
+//! encode/decode around `start_send`/`poll_next` itself.
+
+use futures::{Sink, Stream};
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{Message, SocketError};
+
+/// Serializes a value of type `T` into a wire frame.
+pub trait Encode<T> {
+    type Error;
+
+    fn encode(&mut self, value: &T) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// Deserializes a wire frame back into a value of type `T`.
+pub trait Decode<T> {
+    type Error;
+
+    fn decode(&mut self, data: &[u8]) -> Result<T, Self::Error>;
+}
+
+/// An error arising anywhere in a [`Framed`] socket: serialization,
+/// deserialization, or the underlying transport itself.
+#[derive(Debug)]
+pub enum Error<E, D> {
+    Encode(E),
+    Decode(D),
+    Socket(SocketError),
+    /// A text frame arrived where only binary frames carry codec payloads.
+    UnexpectedText,
+}
+
+impl<E: fmt::Display, D: fmt::Display> fmt::Display for Error<E, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Encode(e) => write!(f, "failed to encode message: {e}"),
+            Error::Decode(e) => write!(f, "failed to decode message: {e}"),
+            Error::Socket(e) => write!(f, "socket error: {e}"),
+            Error::UnexpectedText => write!(f, "received a text frame, expected binary"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display, D: fmt::Debug + fmt::Display> std::error::Error for Error<E, D> {}
+
+/// Adapts a byte-level [`Socket`](crate::Socket) into a
+/// `Stream<Item = Result<In, Error<..>>>` + `Sink<Out>` by encoding and
+/// decoding frames with a pluggable codec `C`.
+pub struct Framed<S, C, In, Out> {
+    socket: S,
+    codec: C,
+    _types: PhantomData<fn() -> (In, Out)>,
+}
+
+impl<S, C, In, Out> Framed<S, C, In, Out> {
+    pub fn new(socket: S, codec: C) -> Self {
+        Framed {
+            socket,
+            codec,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> (S, C) {
+        (self.socket, self.codec)
+    }
+}
+
+impl<S, C, In, Out> Stream for Framed<S, C, In, Out>
+where
+    S: Stream<Item = Result<Message, SocketError>> + Unpin,
+    C: Encode<Out> + Decode<In> + Unpin,
+    In: Unpin,
+{
+    type Item = Result<In, Error<<C as Encode<Out>>::Error, <C as Decode<In>>::Error>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.socket).poll_next(cx) {
+            Poll::Ready(Some(Ok(Message::Binary(data)))) => Poll::Ready(Some(
+                Decode::<In>::decode(&mut self.codec, &data).map_err(Error::Decode),
+            )),
+            Poll::Ready(Some(Ok(Message::Text(_)))) => {
+                Poll::Ready(Some(Err(Error::UnexpectedText)))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(Error::Socket(e)))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S, C, In, Out> Sink<Out> for Framed<S, C, In, Out>
+where
+    S: Sink<Message, Error = wasm_bindgen::JsValue> + Unpin,
+    C: Encode<Out> + Decode<In> + Unpin,
+    Out: Unpin,
+{
+    type Error = Error<<C as Encode<Out>>::Error, <C as Decode<In>>::Error>;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.socket)
+            .poll_ready(cx)
+            .map_err(|e| Error::Socket(SocketError::Ready(e)))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let data = Encode::<Out>::encode(&mut self.codec, &item).map_err(Error::Encode)?;
+        Pin::new(&mut self.socket)
+            .start_send(Message::Binary(data))
+            .map_err(|e| Error::Socket(SocketError::Send(e)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.socket)
+            .poll_flush(cx)
+            .map_err(|e| Error::Socket(SocketError::Flush(e)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.socket)
+            .poll_close(cx)
+            .map_err(|e| Error::Socket(SocketError::Close(e)))
+    }
+}
@@ -1,5 +1,7 @@
 use abstract_ws::{Socket as AbstractSocket, SocketProvider, Url};
 
+pub mod codec;
+
 use futures::{
     channel::{
         mpsc::{unbounded, UnboundedReceiver},
@@ -10,122 +12,400 @@ use futures::{
 };
 use js_sys::Uint8Array;
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
-use web_sys::{MessageEvent, WebSocket};
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
 
 use core::{
+    cell::{Cell, RefCell},
+    fmt,
     pin::Pin,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
+use std::rc::Rc;
+
+/// How often, in milliseconds, the drain timer checks `bufferedAmount`
+/// against the high-water-mark while a send or flush is pending.
+const DRAIN_POLL_INTERVAL_MS: i32 = 50;
+
+/// Default `bufferedAmount` threshold above which `poll_ready` reports
+/// `Poll::Pending`, in bytes.
+pub const DEFAULT_HIGH_WATER_MARK: u32 = 1 << 20;
+
+/// Whether the drain timer should wake the pending waker (and stop itself)
+/// given the current `bufferedAmount` and a threshold. The timer always
+/// passes the high-water-mark, for a pending `poll_flush` as well; a woken
+/// `poll_flush` re-arms the timer while `bufferedAmount` is still above `0`.
+fn should_wake_drain(buffered_amount: u32, threshold: u32) -> bool {
+    buffered_amount <= threshold
+}
+
+/// An error observed over the lifetime of a [`Socket`]. `Transport` and
+/// `Closed` are surfaced through [`Stream::poll_next`] rather than dropped on
+/// the floor; `Send`, `Ready`, `Flush` and `Close` wrap the sink errors that
+/// [`codec::Framed`] reports.
+#[derive(Debug)]
+pub enum SocketError {
+    /// A call to `send_with_u8_array` (or similar) returned an error.
+    Send(JsValue),
+    /// `poll_ready` (including arming the drain-backpressure waker) failed.
+    Ready(JsValue),
+    /// `poll_flush` failed while waiting for the send buffer to drain.
+    Flush(JsValue),
+    /// `poll_close` failed to close the socket.
+    Close(JsValue),
+    /// The transport fired an `onerror` event.
+    Transport(JsValue),
+    /// The socket closed without a clean closing handshake (the `CloseEvent`
+    /// reported `wasClean == false`), whatever its close code.
+    Closed { code: u16, reason: String },
+}
+
+impl fmt::Display for SocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketError::Send(_) => write!(f, "failed to send over websocket"),
+            SocketError::Ready(_) => write!(f, "failed to prepare websocket for sending"),
+            SocketError::Flush(_) => write!(f, "failed to flush websocket send buffer"),
+            SocketError::Close(_) => write!(f, "failed to close websocket"),
+            SocketError::Transport(_) => write!(f, "websocket transport error"),
+            SocketError::Closed { code, reason } => {
+                write!(f, "websocket closed abnormally (code {code}): {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SocketError {}
+
+/// The code, reason, and cleanliness of a websocket's closing handshake, as
+/// reported by the browser's `CloseEvent`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+    pub was_clean: bool,
+}
+
+/// A single websocket frame, either a UTF-8 text frame or a binary frame.
+///
+/// Mirrors `gloo-net`'s message type so callers that need to distinguish the
+/// two aren't forced to guess from raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Message::Binary(data)
+    }
+}
+
+impl From<String> for Message {
+    fn from(data: String) -> Self {
+        Message::Text(data)
+    }
+}
 
 pub struct Socket {
     inner: WebSocket,
     #[allow(dead_code)]
     on_message: Closure<dyn FnMut(MessageEvent)>,
     #[allow(dead_code)]
-    on_close: Closure<dyn FnMut(JsValue)>,
-    messages: UnboundedReceiver<Vec<u8>>,
+    on_error: Closure<dyn FnMut(JsValue)>,
+    #[allow(dead_code)]
+    on_close: Closure<dyn FnMut(CloseEvent)>,
+    #[allow(dead_code)]
+    on_drain_tick: Closure<dyn FnMut()>,
+    drain_interval_id: Rc<Cell<Option<i32>>>,
+    drain_waker: Rc<RefCell<Option<Waker>>>,
+    high_water_mark: Rc<Cell<u32>>,
+    close_frame: Rc<RefCell<Option<CloseFrame>>>,
+    pending_close: Option<(u16, String)>,
+    messages: UnboundedReceiver<Result<Message, SocketError>>,
 }
 
 impl Socket {
     fn new(url: Url) -> impl Future<Output = Result<Self, JsValue>> {
-        let (sender, receiver) = unbounded();
+        Self::connect(WebSocket::new(url.as_ref()))
+    }
+
+    fn new_with_protocols(
+        url: Url,
+        protocols: &[String],
+    ) -> impl Future<Output = Result<Self, JsValue>> {
+        let protocols = protocols
+            .iter()
+            .map(|protocol| JsValue::from_str(protocol))
+            .collect::<js_sys::Array>();
+
+        Self::connect(WebSocket::new_with_str_sequence(url.as_ref(), &protocols))
+    }
 
-        let socket = WebSocket::new(url.as_ref());
+    fn connect(socket: Result<WebSocket, JsValue>) -> impl Future<Output = Result<Self, JsValue>> {
+        let (sender, receiver) = unbounded();
 
         async move {
             let socket = socket?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
 
             let (open_sender, open) = channel();
-            let (error_sender, error) = channel();
+            let (open_error_sender, open_error) = channel();
 
             let mut open_sender = Some(open_sender);
-            let mut error_sender = Some(error_sender);
+            let mut open_error_sender = Some(open_error_sender);
 
             let on_open = Closure::wrap(Box::new(move |_: JsValue| {
                 let _ = open_sender.take().map(|sender| sender.send(None));
             }) as Box<dyn FnMut(_)>);
 
-            let on_error = Closure::wrap(Box::new(move |e: JsValue| {
-                let _ = error_sender.take().map(|sender| sender.send(Some(e)));
+            let on_open_error = Closure::wrap(Box::new(move |e: JsValue| {
+                let _ = open_error_sender.take().map(|sender| sender.send(Some(e)));
             }) as Box<dyn FnMut(_)>);
 
             socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
-            socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            socket.set_onerror(Some(on_open_error.as_ref().unchecked_ref()));
 
-            let res = select(open, error).await.factor_first().0.unwrap();
+            let res = select(open, open_error).await.factor_first().0.unwrap();
 
             socket.set_onopen(None);
-            socket.set_onerror(None);
+
+            if let Some(res) = res {
+                socket.set_onerror(None);
+                return Err(res);
+            }
 
             let on_message = {
                 let sender = sender.clone();
 
                 Closure::wrap(Box::new(move |e: MessageEvent| {
-                    let buffer = Uint8Array::new(&e.data());
-                    let mut data = vec![0u8; buffer.length() as usize];
-                    buffer.copy_to(&mut data);
-                    let _ = sender.unbounded_send(data);
+                    let message = if let Some(text) = e.data().as_string() {
+                        Message::Text(text)
+                    } else {
+                        let buffer = Uint8Array::new(&e.data());
+                        let mut data = vec![0u8; buffer.length() as usize];
+                        buffer.copy_to(&mut data);
+                        Message::Binary(data)
+                    };
+                    let _ = sender.unbounded_send(Ok(message));
                 }) as Box<dyn FnMut(_)>)
             };
 
             socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
 
-            let on_close = Closure::wrap(Box::new(move |_: JsValue| {
-                sender.close_channel();
-            }) as Box<dyn FnMut(_)>);
+            // Replace the handshake-only `onerror` with one that lives for the
+            // rest of the socket's life, forwarding runtime errors into the
+            // same channel the messages arrive on.
+            let on_error = {
+                let sender = sender.clone();
+
+                Closure::wrap(Box::new(move |e: JsValue| {
+                    let _ = sender.unbounded_send(Err(SocketError::Transport(e)));
+                }) as Box<dyn FnMut(_)>)
+            };
+
+            socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+            let close_frame: Rc<RefCell<Option<CloseFrame>>> = Rc::new(RefCell::new(None));
+
+            let on_close = {
+                let sender = sender.clone();
+                let close_frame = close_frame.clone();
+
+                Closure::wrap(Box::new(move |e: CloseEvent| {
+                    let frame = CloseFrame {
+                        code: e.code(),
+                        reason: e.reason(),
+                        was_clean: e.was_clean(),
+                    };
+
+                    if !frame.was_clean {
+                        let _ = sender.unbounded_send(Err(SocketError::Closed {
+                            code: frame.code,
+                            reason: frame.reason.clone(),
+                        }));
+                    }
+
+                    *close_frame.borrow_mut() = Some(frame);
+                    sender.close_channel();
+                }) as Box<dyn FnMut(_)>)
+            };
 
             socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
 
-            if let Some(res) = res {
-                Err(res)
-            } else {
-                Ok(Socket {
-                    messages: receiver,
-                    on_message,
-                    on_close,
-                    inner: socket,
-                })
-            }
+            let drain_waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+            let drain_interval_id: Rc<Cell<Option<i32>>> = Rc::new(Cell::new(None));
+            let high_water_mark = Rc::new(Cell::new(DEFAULT_HIGH_WATER_MARK));
+
+            // Checks `bufferedAmount` itself before waking, and stops the
+            // timer once it does — `arm_drain_waker` restarts it if a later
+            // poll still needs to wait, so idle sockets pay nothing.
+            let on_drain_tick = {
+                let drain_waker = drain_waker.clone();
+                let drain_interval_id = drain_interval_id.clone();
+                let high_water_mark = high_water_mark.clone();
+                let socket = socket.clone();
+
+                Closure::wrap(Box::new(move || {
+                    if !should_wake_drain(socket.buffered_amount(), high_water_mark.get()) {
+                        return;
+                    }
+
+                    if let Some(waker) = drain_waker.borrow_mut().take() {
+                        waker.wake();
+                    }
+
+                    if let Some(id) = drain_interval_id.take() {
+                        if let Some(window) = web_sys::window() {
+                            window.clear_interval_with_handle(id);
+                        }
+                    }
+                }) as Box<dyn FnMut()>)
+            };
+
+            Ok(Socket {
+                messages: receiver,
+                on_message,
+                on_error,
+                on_close,
+                on_drain_tick,
+                drain_interval_id,
+                drain_waker,
+                high_water_mark,
+                close_frame,
+                pending_close: None,
+                inner: socket,
+            })
+        }
+    }
+
+    /// Registers `waker` to be woken once `bufferedAmount` drains back
+    /// below the high-water-mark, starting the drain timer if it isn't
+    /// already running.
+    fn arm_drain_waker(&self, waker: Waker) -> Result<(), JsValue> {
+        *self.drain_waker.borrow_mut() = Some(waker);
+
+        if self.drain_interval_id.get().is_none() {
+            let window =
+                web_sys::window().ok_or_else(|| JsValue::from_str("no global `window` exists"))?;
+            let id = window.set_interval_with_callback_and_timeout_and_arguments_0(
+                self.on_drain_tick.as_ref().unchecked_ref(),
+                DRAIN_POLL_INTERVAL_MS,
+            )?;
+            self.drain_interval_id.set(Some(id));
         }
+
+        Ok(())
+    }
+
+    /// Sets the `bufferedAmount` (bytes) above which `poll_ready` starts
+    /// reporting `Poll::Pending` until the browser has drained its send
+    /// buffer back below the mark.
+    pub fn set_high_water_mark(&mut self, bytes: u32) {
+        self.high_water_mark.set(bytes);
+    }
+
+    /// The code, reason, and cleanliness of the closing handshake, once the
+    /// stream has terminated. `None` until the socket has closed.
+    pub fn close_frame(&self) -> Option<CloseFrame> {
+        self.close_frame.borrow().clone()
+    }
+
+    /// Sets the code and reason [`Sink::poll_close`] sends via
+    /// `close_with_code_and_reason`, instead of the default no-argument
+    /// `close()`.
+    pub fn set_close_frame(&mut self, code: u16, reason: impl Into<String>) {
+        self.pending_close = Some((code, reason.into()));
+    }
+
+    /// The subprotocol negotiated with the server during the opening
+    /// handshake, or the empty string if none was requested or accepted.
+    pub fn protocol(&self) -> String {
+        self.inner.protocol()
     }
 }
 
 impl Drop for Socket {
     fn drop(&mut self) {
         let _ = self.inner.close();
+
+        if let Some(id) = self.drain_interval_id.take() {
+            if let Some(window) = web_sys::window() {
+                window.clear_interval_with_handle(id);
+            }
+        }
     }
 }
 
 impl Stream for Socket {
-    type Item = Vec<u8>;
+    type Item = Result<Message, SocketError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         Pin::new(&mut self.messages).poll_next(cx)
     }
 }
 
-impl Sink<Vec<u8>> for Socket {
+impl Sink<Message> for Socket {
     type Error = JsValue;
 
-    fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.inner.buffered_amount() > self.high_water_mark.get() {
+            self.arm_drain_waker(cx.waker().clone())?;
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
     }
 
-    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
-        self.inner.send_with_u8_array(&item)
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        match item {
+            Message::Text(text) => self.inner.send_with_str(&text),
+            Message::Binary(data) => self.inner.send_with_u8_array(&data),
+        }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.inner.buffered_amount() > 0 {
+            self.arm_drain_waker(cx.waker().clone())?;
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.inner.close()?;
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if let Some((code, reason)) = self.pending_close.take() {
+            self.inner.close_with_code_and_reason(code, &reason)?;
+        } else {
+            self.inner.close()?;
+        }
 
         Poll::Ready(Ok(()))
     }
 }
 
+/// Compatibility shim for existing callers that only ever dealt in raw
+/// bytes; dispatches through the `Message` sink as a binary frame.
+impl Sink<Vec<u8>> for Socket {
+    type Error = JsValue;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Sink::<Message>::poll_ready(self, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        Sink::<Message>::start_send(self, Message::Binary(item))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Sink::<Message>::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Sink::<Message>::poll_close(self, cx)
+    }
+}
+
 impl AbstractSocket for Socket {}
 
 pub struct Provider;
@@ -138,3 +418,40 @@ impl SocketProvider for Provider {
         Box::pin(Socket::new(url))
     }
 }
+
+impl Provider {
+    /// Connects requesting the given subprotocols, in preference order. The
+    /// one the server accepted is readable afterwards via
+    /// [`Socket::protocol`].
+    pub fn connect_with_protocols(
+        &self,
+        url: Url,
+        protocols: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<Socket, JsValue>>>> {
+        Box::pin(Socket::new_with_protocols(url, protocols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{should_wake_drain, DEFAULT_HIGH_WATER_MARK};
+
+    #[test]
+    fn wakes_once_buffered_amount_reaches_the_threshold() {
+        assert!(should_wake_drain(0, 0));
+        assert!(should_wake_drain(0, DEFAULT_HIGH_WATER_MARK));
+        assert!(should_wake_drain(
+            DEFAULT_HIGH_WATER_MARK,
+            DEFAULT_HIGH_WATER_MARK
+        ));
+    }
+
+    #[test]
+    fn stays_pending_above_the_threshold() {
+        assert!(!should_wake_drain(1, 0));
+        assert!(!should_wake_drain(
+            DEFAULT_HIGH_WATER_MARK + 1,
+            DEFAULT_HIGH_WATER_MARK
+        ));
+    }
+}